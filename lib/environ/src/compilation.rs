@@ -1,33 +1,237 @@
 //! A `Compilation` contains the compiled function bodies for a WebAssembly
 //! module.
 
+use bitflags::bitflags;
 use cranelift_codegen::binemit;
 use cranelift_codegen::ir;
 use cranelift_codegen::ir::ExternalName;
 use cranelift_codegen::isa;
 use cranelift_codegen::{CodegenError, Context};
-use cranelift_entity::{EntityRef, PrimaryMap};
+use cranelift_entity::{entity_impl, EntityRef, PrimaryMap};
 use cranelift_wasm::{DefinedFuncIndex, FuncIndex, FuncTranslator, WasmError};
 use environ::{get_func_name, get_memory_grow_name, get_memory_size_name, FuncEnvironment};
 use module::Module;
+#[cfg(feature = "parallel-compilation")]
+use rayon::prelude::*;
 use std::vec::Vec;
 
+/// The index of a custom, compiler-generated section of code living
+/// alongside the compiled function bodies, e.g. a libcall trampoline.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SectionIndex(u32);
+entity_impl!(SectionIndex);
+
+bitflags! {
+    /// CPU features that generated code may rely on being present on the
+    /// host. Stored as a compact bitset so a `Compilation` can carry it
+    /// around cheaply and compare it against the host's detected features
+    /// before any generated code runs.
+    #[derive(Default)]
+    pub struct CpuFeature: u32 {
+        /// SSE2 instructions.
+        const SSE2 = 1 << 0;
+        /// SSE3 instructions.
+        const SSE3 = 1 << 1;
+        /// SSSE3 instructions.
+        const SSSE3 = 1 << 2;
+        /// SSE4.1 instructions.
+        const SSE41 = 1 << 3;
+        /// SSE4.2 instructions.
+        const SSE42 = 1 << 4;
+        /// AVX instructions.
+        const AVX = 1 << 5;
+        /// AVX2 instructions.
+        const AVX2 = 1 << 6;
+        /// BMI1 instructions.
+        const BMI1 = 1 << 7;
+        /// BMI2 instructions.
+        const BMI2 = 1 << 8;
+        /// LZCNT instruction.
+        const LZCNT = 1 << 9;
+        /// POPCNT instruction.
+        const POPCNT = 1 << 10;
+        /// FMA instructions.
+        const FMA = 1 << 11;
+    }
+}
+
+impl CpuFeature {
+    /// Collects the set of `CpuFeature`s that `isa` has enabled, by
+    /// inspecting its ISA-specific settings.
+    ///
+    /// Every boolean-valued, enabled ISA flag must map to a `CpuFeature`
+    /// bit: an enabled flag this function doesn't recognize means
+    /// `ensure_cpu_features_supported` would silently pass a host that's
+    /// actually missing that instruction, so that case panics instead of
+    /// continuing past it.
+    fn from_isa(isa: &isa::TargetIsa) -> Self {
+        let mut features = CpuFeature::empty();
+        for value in isa.isa_flags() {
+            let enabled = match value.as_bool() {
+                Some(enabled) => enabled,
+                None => continue,
+            };
+            if !enabled {
+                continue;
+            }
+            let flag = match value.name() {
+                "has_sse2" => CpuFeature::SSE2,
+                "has_sse3" => CpuFeature::SSE3,
+                "has_ssse3" => CpuFeature::SSSE3,
+                "has_sse41" => CpuFeature::SSE41,
+                "has_sse42" => CpuFeature::SSE42,
+                "has_avx" => CpuFeature::AVX,
+                "has_avx2" => CpuFeature::AVX2,
+                "has_bmi1" => CpuFeature::BMI1,
+                "has_bmi2" => CpuFeature::BMI2,
+                "has_lzcnt" => CpuFeature::LZCNT,
+                "has_popcnt" => CpuFeature::POPCNT,
+                "has_fma" => CpuFeature::FMA,
+                other => panic!(
+                    "ISA flag {:?} is enabled but has no corresponding CpuFeature bit; \
+                     code generated using it could crash on a host that's missing it",
+                    other
+                ),
+            };
+            features |= flag;
+        }
+        features
+    }
+
+    /// Returns the `CpuFeature`s the host this code is currently running
+    /// on actually supports.
+    #[cfg(target_arch = "x86_64")]
+    pub fn detect_host() -> Self {
+        let mut features = CpuFeature::empty();
+        if is_x86_feature_detected!("sse2") {
+            features |= CpuFeature::SSE2;
+        }
+        if is_x86_feature_detected!("sse3") {
+            features |= CpuFeature::SSE3;
+        }
+        if is_x86_feature_detected!("ssse3") {
+            features |= CpuFeature::SSSE3;
+        }
+        if is_x86_feature_detected!("sse4.1") {
+            features |= CpuFeature::SSE41;
+        }
+        if is_x86_feature_detected!("sse4.2") {
+            features |= CpuFeature::SSE42;
+        }
+        if is_x86_feature_detected!("avx") {
+            features |= CpuFeature::AVX;
+        }
+        if is_x86_feature_detected!("avx2") {
+            features |= CpuFeature::AVX2;
+        }
+        if is_x86_feature_detected!("bmi1") {
+            features |= CpuFeature::BMI1;
+        }
+        if is_x86_feature_detected!("bmi2") {
+            features |= CpuFeature::BMI2;
+        }
+        if is_x86_feature_detected!("lzcnt") {
+            features |= CpuFeature::LZCNT;
+        }
+        if is_x86_feature_detected!("popcnt") {
+            features |= CpuFeature::POPCNT;
+        }
+        if is_x86_feature_detected!("fma") {
+            features |= CpuFeature::FMA;
+        }
+        features
+    }
+
+    /// Returns the `CpuFeature`s the host this code is currently running
+    /// on actually supports.
+    #[cfg(not(target_arch = "x86_64"))]
+    pub fn detect_host() -> Self {
+        CpuFeature::empty()
+    }
+}
+
 /// The result of compiling a WebAssemby module's functions.
 #[derive(Debug)]
 pub struct Compilation {
     /// Compiled machine code for the function bodies.
     pub functions: PrimaryMap<DefinedFuncIndex, Vec<u8>>,
+
+    /// Additional read-execute sections emitted alongside the function
+    /// bodies. These are libcall trampolines and jump-table payloads.
+    ///
+    /// A trampoline is a short stub of code, one per distinct libcall,
+    /// that indirects through an 8-byte absolute address slot. Function
+    /// bodies relocate to a trampoline instead of calling the libcall
+    /// directly, since the trampoline is guaranteed to be within 32-bit
+    /// relative range of the calling code while the libcall itself may
+    /// not be. Jump-table payloads are described under `jump_tables`.
+    pub custom_sections: PrimaryMap<SectionIndex, Vec<u8>>,
+
+    /// Relocations to apply to `custom_sections`, keyed the same way.
+    pub custom_section_relocations: PrimaryMap<SectionIndex, Vec<Relocation>>,
+
+    /// The CPU features the target ISA enabled while compiling this
+    /// module. A host must support all of these before running the
+    /// generated code.
+    pub cpu_features: CpuFeature,
+
+    /// The resolved layout of every jump table, kept around for
+    /// inspection/debugging. The actual jump table bytes that get patched
+    /// at load time live in `custom_sections`: `compile_module` emits one
+    /// custom section per jump table (one `Abs4` slot per entry, each
+    /// relocated to its target EBB via `RelocationTarget::FunctionBody`)
+    /// and rewrites the originating `RelocationTarget::JumpTable` to point
+    /// at it, mirroring how libcall relocations are rewritten to point at
+    /// trampolines.
+    pub jump_tables: JumpTables,
 }
 
 impl Compilation {
-    /// Allocates the compilation result with the given function bodies.
-    pub fn new(functions: PrimaryMap<DefinedFuncIndex, Vec<u8>>) -> Self {
-        Self { functions }
+    /// Allocates the compilation result with the given function bodies and
+    /// custom sections.
+    pub fn new(
+        functions: PrimaryMap<DefinedFuncIndex, Vec<u8>>,
+        custom_sections: PrimaryMap<SectionIndex, Vec<u8>>,
+        custom_section_relocations: PrimaryMap<SectionIndex, Vec<Relocation>>,
+        cpu_features: CpuFeature,
+        jump_tables: JumpTables,
+    ) -> Self {
+        Self {
+            functions,
+            custom_sections,
+            custom_section_relocations,
+            cpu_features,
+            jump_tables,
+        }
+    }
+
+    /// Checks that `host_features` supports every CPU feature this
+    /// compilation requires, returning a structured error naming whatever
+    /// is missing. Call this before running the compiled code on a host
+    /// that may differ from the one it was compiled on.
+    pub fn ensure_cpu_features_supported(
+        &self,
+        host_features: CpuFeature,
+    ) -> Result<(), UnsupportedCpuFeatures> {
+        let missing = self.cpu_features & !host_features;
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(UnsupportedCpuFeatures(missing))
+        }
     }
 }
 
+/// The error returned when a `Compilation` requires CPU features that the
+/// host it's about to run on does not support.
+#[derive(Fail, Debug)]
+#[fail(display = "host is missing required CPU feature(s): {:?}", _0)]
+pub struct UnsupportedCpuFeatures(pub CpuFeature);
+
 /// Implementation of a relocation sink that just saves all the information for later
 pub struct RelocSink {
+    /// The function this sink is recording relocations for.
+    func_index: DefinedFuncIndex,
     /// Relocations recorded for the function.
     pub func_relocs: Vec<Relocation>,
 }
@@ -35,12 +239,19 @@ pub struct RelocSink {
 impl binemit::RelocSink for RelocSink {
     fn reloc_ebb(
         &mut self,
-        _offset: binemit::CodeOffset,
-        _reloc: binemit::Reloc,
-        _ebb_offset: binemit::CodeOffset,
+        offset: binemit::CodeOffset,
+        reloc: binemit::Reloc,
+        ebb_offset: binemit::CodeOffset,
     ) {
-        // This should use the `offsets` field of `ir::Function`.
-        panic!("ebb headers not yet implemented");
+        // EBB headers only ever refer to a location within the function
+        // currently being compiled; Cranelift has already resolved
+        // `ebb_offset` via the `offsets` field of `ir::Function`.
+        self.func_relocs.push(Relocation {
+            reloc,
+            reloc_target: RelocationTarget::FunctionBody(self.func_index),
+            offset,
+            addend: ebb_offset as binemit::Addend,
+        });
     }
     fn reloc_external(
         &mut self,
@@ -68,25 +279,70 @@ impl binemit::RelocSink for RelocSink {
             addend,
         });
     }
-    fn reloc_jt(
-        &mut self,
-        _offset: binemit::CodeOffset,
-        _reloc: binemit::Reloc,
-        _jt: ir::JumpTable,
-    ) {
-        panic!("jump tables not yet implemented");
+    fn reloc_jt(&mut self, offset: binemit::CodeOffset, reloc: binemit::Reloc, jt: ir::JumpTable) {
+        self.func_relocs.push(Relocation {
+            reloc,
+            reloc_target: RelocationTarget::JumpTable(self.func_index, jt),
+            offset,
+            addend: 0,
+        });
     }
 }
 
 impl RelocSink {
-    /// Return a new `RelocSink` instance.
-    pub fn new() -> Self {
+    /// Return a new `RelocSink` instance for recording relocations in
+    /// `func_index`.
+    pub fn new(func_index: DefinedFuncIndex) -> Self {
         Self {
+            func_index,
             func_relocs: Vec::new(),
         }
     }
 }
 
+/// Implementation of a trap sink that simply stores all trap information
+/// in memory, mirroring `RelocSink`.
+pub struct TrapSink {
+    /// Trap information recorded for the function.
+    pub traps: Vec<TrapInformation>,
+}
+
+impl TrapSink {
+    /// Create a new `TrapSink`.
+    pub fn new() -> Self {
+        Self { traps: Vec::new() }
+    }
+}
+
+impl binemit::TrapSink for TrapSink {
+    fn trap(
+        &mut self,
+        code_offset: binemit::CodeOffset,
+        _source_loc: ir::SourceLoc,
+        trap_code: ir::TrapCode,
+    ) {
+        self.traps.push(TrapInformation {
+            code_offset,
+            trap_code,
+        });
+    }
+}
+
+/// A record of a trap site: the offset within a function's compiled code
+/// where a trapping instruction was emitted, and why it traps. This lets
+/// the runtime map a faulting instruction pointer back to a precise
+/// WebAssembly trap reason instead of reporting a generic fault.
+#[derive(Debug, Clone, Copy)]
+pub struct TrapInformation {
+    /// The offset of the trapping instruction within the function's body.
+    pub code_offset: binemit::CodeOffset,
+    /// The trap code describing why the trap would fire.
+    pub trap_code: ir::TrapCode,
+}
+
+/// Trap sites recorded for all of a module's functions.
+pub type Traps = PrimaryMap<DefinedFuncIndex, Vec<TrapInformation>>;
+
 /// A record of a relocation to perform.
 #[derive(Debug, Clone)]
 pub struct Relocation {
@@ -111,47 +367,210 @@ pub enum RelocationTarget {
     MemoryGrow,
     /// Function for query current size of the default linear memory.
     MemorySize,
+    /// A custom section emitted alongside the function bodies, e.g. a
+    /// libcall trampoline or a jump table's payload.
+    CustomSection(SectionIndex),
+    /// A jump table belonging to the named function. `compile_module`
+    /// rewrites this to a `CustomSection` once it has emitted the table's
+    /// payload, so this variant should never reach a loader.
+    JumpTable(DefinedFuncIndex, ir::JumpTable),
+    /// A location within the named function's own compiled code, e.g. an
+    /// EBB header reached through a jump table.
+    FunctionBody(DefinedFuncIndex),
 }
 
 /// Relocations to apply to function bodies.
 pub type Relocations = PrimaryMap<DefinedFuncIndex, Vec<Relocation>>;
 
+/// For each jump table emitted for a function, the resolved code offset of
+/// every EBB it can branch to. All of these offsets are within that same
+/// function's own body.
+pub type JumpTableLayout = PrimaryMap<ir::JumpTable, Vec<binemit::CodeOffset>>;
+
+/// Jump-table layouts emitted alongside each function body. A loader does
+/// not need these: `compile_module` has already rewritten every
+/// `RelocationTarget::JumpTable` to the `CustomSection` holding the table.
+pub type JumpTables = PrimaryMap<DefinedFuncIndex, JumpTableLayout>;
+
+/// Collects the resolved layout of every jump table `func` emits, using
+/// the EBB offsets Cranelift records on `ir::Function` once compilation
+/// has finished.
+fn collect_jump_tables(func: &ir::Function) -> JumpTableLayout {
+    let mut tables = PrimaryMap::new();
+    for (_, jt_data) in func.jump_tables.iter() {
+        let offsets = jt_data.iter().map(|ebb| func.offsets[*ebb]).collect();
+        tables.push(offsets);
+    }
+    tables
+}
+
+/// Builds a trampoline that indirects through an 8-byte absolute-address
+/// slot, for use as the relocation target of a libcall that may be more
+/// than 2GB away from the calling code.
+///
+/// The stub is `jmp qword ptr [rip+0]` (six bytes) immediately followed by
+/// the 8-byte slot, which the loader patches with the libcall's real
+/// address. Returns the stub's bytes along with the `Abs8` relocation to
+/// apply to the slot.
+fn make_libcall_trampoline(libcall: ir::LibCall) -> (Vec<u8>, Relocation) {
+    let mut code = vec![0xff, 0x25, 0x00, 0x00, 0x00, 0x00];
+    let slot_offset = code.len() as binemit::CodeOffset;
+    code.extend_from_slice(&[0; 8]);
+    let reloc = Relocation {
+        reloc: binemit::Reloc::Abs8,
+        reloc_target: RelocationTarget::LibCall(libcall),
+        offset: slot_offset,
+        addend: 0,
+    };
+    (code, reloc)
+}
+
+/// Builds the on-disk payload for a jump table: one 4-byte slot per entry,
+/// each carrying its own relocation back into the owning function's body
+/// at that entry's resolved offset. The slots start out zeroed; the
+/// loader patches each one once it knows where the function was loaded.
+fn make_jump_table_payload(
+    func_index: DefinedFuncIndex,
+    entries: &[binemit::CodeOffset],
+) -> (Vec<u8>, Vec<Relocation>) {
+    let mut code = Vec::with_capacity(entries.len() * 4);
+    let mut relocs = Vec::with_capacity(entries.len());
+    for &entry_offset in entries {
+        let slot_offset = code.len() as binemit::CodeOffset;
+        code.extend_from_slice(&[0; 4]);
+        relocs.push(Relocation {
+            reloc: binemit::Reloc::Abs4,
+            reloc_target: RelocationTarget::FunctionBody(func_index),
+            offset: slot_offset,
+            addend: entry_offset as binemit::Addend,
+        });
+    }
+    (code, relocs)
+}
+
+/// Compiles a single function, translating its body and emitting machine
+/// code for it. This is independent of every other function: `module` and
+/// `isa` are only read from, so this can safely be called concurrently for
+/// distinct functions of the same module.
+fn compile_function(
+    module: &Module,
+    defined_index: DefinedFuncIndex,
+    func_index: FuncIndex,
+    input: &[u8],
+    isa: &isa::TargetIsa,
+) -> Result<(Vec<u8>, Vec<Relocation>, Vec<TrapInformation>, JumpTableLayout), CompileError> {
+    let mut context = Context::new();
+    context.func.name = get_func_name(func_index);
+    context.func.signature = module.signatures[module.functions[func_index]].clone();
+
+    let mut trans = FuncTranslator::new();
+    trans
+        .translate(
+            input,
+            &mut context.func,
+            &mut FuncEnvironment::new(isa, module),
+        )
+        .map_err(CompileError::Wasm)?;
+
+    let mut code_buf: Vec<u8> = Vec::new();
+    let mut reloc_sink = RelocSink::new(defined_index);
+    let mut trap_sink = TrapSink::new();
+    context
+        .compile_and_emit(isa, &mut code_buf, &mut reloc_sink, &mut trap_sink)
+        .map_err(CompileError::Codegen)?;
+
+    let jump_tables = collect_jump_tables(&context.func);
+
+    Ok((code_buf, reloc_sink.func_relocs, trap_sink.traps, jump_tables))
+}
+
 /// Compile the module, producing a compilation result with associated
 /// relocations.
 pub fn compile_module<'data, 'module>(
     module: &'module Module,
     function_body_inputs: &PrimaryMap<DefinedFuncIndex, &'data [u8]>,
     isa: &isa::TargetIsa,
-) -> Result<(Compilation, Relocations), CompileError> {
+) -> Result<(Compilation, Relocations, Traps), CompileError> {
+    let inputs: Vec<(DefinedFuncIndex, &'data [u8])> = function_body_inputs
+        .iter()
+        .map(|(i, &input)| (i, input))
+        .collect();
+
+    // Compile every function independently. When the `parallel-compilation`
+    // feature is enabled this farms the jobs out across a rayon thread
+    // pool; either way the raw outputs below come back in the same order
+    // as `inputs`, i.e. in `DefinedFuncIndex` order.
+    #[cfg(feature = "parallel-compilation")]
+    let raw_outputs = inputs
+        .into_par_iter()
+        .map(|(i, input)| compile_function(module, i, module.func_index(i), input, isa))
+        .collect::<Result<Vec<_>, CompileError>>()?;
+    #[cfg(not(feature = "parallel-compilation"))]
+    let raw_outputs = inputs
+        .into_iter()
+        .map(|(i, input)| compile_function(module, i, module.func_index(i), input, isa))
+        .collect::<Result<Vec<_>, CompileError>>()?;
+
     let mut functions = PrimaryMap::new();
     let mut relocations = PrimaryMap::new();
-    for (i, input) in function_body_inputs.iter() {
-        let func_index = module.func_index(i);
-        let mut context = Context::new();
-        context.func.name = get_func_name(func_index);
-        context.func.signature = module.signatures[module.functions[func_index]].clone();
-
-        let mut trans = FuncTranslator::new();
-        trans
-            .translate(
-                input,
-                &mut context.func,
-                &mut FuncEnvironment::new(isa, module),
-            )
-            .map_err(CompileError::Wasm)?;
-
-        let mut code_buf: Vec<u8> = Vec::new();
-        let mut reloc_sink = RelocSink::new();
-        let mut trap_sink = binemit::NullTrapSink {};
-        context
-            .compile_and_emit(isa, &mut code_buf, &mut reloc_sink, &mut trap_sink)
-            .map_err(CompileError::Codegen)?;
+    let mut traps = PrimaryMap::new();
+    let mut jump_tables = PrimaryMap::new();
+    let mut custom_sections = PrimaryMap::new();
+    let mut custom_section_relocations = PrimaryMap::new();
+    // Maps a libcall to the trampoline we've already emitted for it, so
+    // that repeated relocations to the same libcall share one trampoline.
+    let mut libcall_trampolines: Vec<(ir::LibCall, SectionIndex)> = Vec::new();
+    for (code_buf, mut func_relocs, func_traps, func_jump_tables) in raw_outputs {
+        for reloc in &mut func_relocs {
+            let new_target = match reloc.reloc_target {
+                RelocationTarget::LibCall(libcall) => {
+                    let section_index = match libcall_trampolines
+                        .iter()
+                        .find(|(target, _)| *target == libcall)
+                    {
+                        Some((_, section_index)) => *section_index,
+                        None => {
+                            let (code, section_reloc) = make_libcall_trampoline(libcall);
+                            let section_index = custom_sections.push(code);
+                            custom_section_relocations.push(vec![section_reloc]);
+                            libcall_trampolines.push((libcall, section_index));
+                            section_index
+                        }
+                    };
+                    RelocationTarget::CustomSection(section_index)
+                }
+                RelocationTarget::JumpTable(func_index, jt) => {
+                    let (code, section_relocs) =
+                        make_jump_table_payload(func_index, &func_jump_tables[jt]);
+                    let section_index = custom_sections.push(code);
+                    custom_section_relocations.push(section_relocs);
+                    RelocationTarget::CustomSection(section_index)
+                }
+                _ => continue,
+            };
+            reloc.reloc_target = new_target;
+        }
+
         functions.push(code_buf);
-        relocations.push(reloc_sink.func_relocs);
+        relocations.push(func_relocs);
+        traps.push(func_traps);
+        jump_tables.push(func_jump_tables);
     }
 
+    let cpu_features = CpuFeature::from_isa(isa);
+
     // TODO: Reorganize where we create the Vec for the resolved imports.
-    Ok((Compilation::new(functions), relocations))
+    Ok((
+        Compilation::new(
+            functions,
+            custom_sections,
+            custom_section_relocations,
+            cpu_features,
+            jump_tables,
+        ),
+        relocations,
+        traps,
+    ))
 }
 
 /// An error while compiling WebAssembly to machine code.
@@ -164,4 +583,128 @@ pub enum CompileError {
     /// A compilation error occured.
     #[fail(display = "Compilation error: {}", _0)]
     Codegen(CodegenError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compilation_requiring(cpu_features: CpuFeature) -> Compilation {
+        Compilation::new(
+            PrimaryMap::new(),
+            PrimaryMap::new(),
+            PrimaryMap::new(),
+            cpu_features,
+            PrimaryMap::new(),
+        )
+    }
+
+    #[test]
+    fn missing_cpu_feature_is_reported() {
+        let compilation = compilation_requiring(CpuFeature::SSE2 | CpuFeature::AVX);
+        let host_features = CpuFeature::SSE2;
+        let err = compilation
+            .ensure_cpu_features_supported(host_features)
+            .expect_err("AVX is not in the host feature set");
+        assert_eq!(err.0, CpuFeature::AVX);
+    }
+
+    #[test]
+    fn superset_host_is_supported() {
+        let compilation = compilation_requiring(CpuFeature::SSE2 | CpuFeature::SSE3);
+        let host_features = CpuFeature::SSE2 | CpuFeature::SSE3 | CpuFeature::AVX;
+        assert!(compilation
+            .ensure_cpu_features_supported(host_features)
+            .is_ok());
+    }
+
+    #[test]
+    fn no_required_features_always_supported() {
+        let compilation = compilation_requiring(CpuFeature::empty());
+        assert!(compilation
+            .ensure_cpu_features_supported(CpuFeature::empty())
+            .is_ok());
+    }
+
+    #[test]
+    fn libcall_trampoline_byte_layout() {
+        let (code, reloc) = make_libcall_trampoline(ir::LibCall::Probestack);
+
+        // `jmp qword ptr [rip+0]` followed by the 8-byte address slot.
+        assert_eq!(
+            code,
+            vec![0xff, 0x25, 0x00, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        assert_eq!(reloc.reloc, binemit::Reloc::Abs8);
+        assert_eq!(reloc.offset, 6);
+        assert_eq!(reloc.addend, 0);
+        match reloc.reloc_target {
+            RelocationTarget::LibCall(ir::LibCall::Probestack) => {}
+            other => panic!("expected a Probestack libcall target, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn trap_sink_records_offset_and_code_in_order() {
+        use binemit::TrapSink as _;
+
+        let mut sink = TrapSink::new();
+        sink.trap(4, Default::default(), ir::TrapCode::HeapOutOfBounds);
+        sink.trap(12, Default::default(), ir::TrapCode::IntegerDivisionByZero);
+
+        assert_eq!(sink.traps.len(), 2);
+        assert_eq!(sink.traps[0].code_offset, 4);
+        assert_eq!(sink.traps[0].trap_code, ir::TrapCode::HeapOutOfBounds);
+        assert_eq!(sink.traps[1].code_offset, 12);
+        assert_eq!(
+            sink.traps[1].trap_code,
+            ir::TrapCode::IntegerDivisionByZero
+        );
+    }
+
+    #[test]
+    fn jump_table_payload_byte_layout() {
+        let (code, relocs) = make_jump_table_payload(DefinedFuncIndex::new(0), &[16, 32, 48]);
+
+        // One zeroed 4-byte slot per entry.
+        assert_eq!(code, vec![0u8; 12]);
+        assert_eq!(relocs.len(), 3);
+
+        for (i, (reloc, &entry_offset)) in relocs.iter().zip(&[16, 32, 48]).enumerate() {
+            assert_eq!(reloc.reloc, binemit::Reloc::Abs4);
+            assert_eq!(reloc.offset, (i * 4) as binemit::CodeOffset);
+            assert_eq!(reloc.addend, entry_offset as binemit::Addend);
+            match reloc.reloc_target {
+                RelocationTarget::FunctionBody(index) => {
+                    assert_eq!(index, DefinedFuncIndex::new(0))
+                }
+                other => panic!("expected a FunctionBody target, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn jump_table_payload_is_empty_for_no_entries() {
+        let (code, relocs) = make_jump_table_payload(DefinedFuncIndex::new(0), &[]);
+        assert!(code.is_empty());
+        assert!(relocs.is_empty());
+    }
+
+    #[test]
+    fn collect_jump_tables_resolves_ebb_offsets() {
+        let mut func = ir::Function::new();
+        let ebb0 = func.dfg.make_ebb();
+        let ebb1 = func.dfg.make_ebb();
+        func.offsets[ebb0] = 10;
+        func.offsets[ebb1] = 20;
+
+        let mut jt_data = ir::JumpTableData::new();
+        jt_data.push_entry(ebb0);
+        jt_data.push_entry(ebb1);
+        let jt = func.create_jump_table(jt_data);
+
+        let tables = collect_jump_tables(&func);
+        assert_eq!(tables[jt], vec![10, 20]);
+    }
 }
\ No newline at end of file
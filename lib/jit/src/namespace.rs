@@ -4,7 +4,9 @@
 
 use super::HashMap;
 use crate::resolver::Resolver;
+use std::boxed::Box;
 use std::string::String;
+use std::vec::Vec;
 use wasmtime_runtime::{Export, Instance};
 
 /// A namespace containing instances keyed by name.
@@ -14,6 +16,12 @@ use wasmtime_runtime::{Export, Instance};
 pub struct Namespace {
     /// Mapping from identifiers to indices in `self.instances`.
     names: HashMap<String, Instance>,
+
+    /// Other resolvers consulted, in order, when `names` doesn't have a
+    /// match. This lets a `Namespace` be composed with other instance
+    /// collections or host-provided resolvers without the caller having
+    /// to reimplement the `Resolver` trait.
+    fallback_resolvers: Vec<Box<dyn Resolver>>,
 }
 
 impl Namespace {
@@ -21,6 +29,7 @@ impl Namespace {
     pub fn new() -> Self {
         Self {
             names: HashMap::new(),
+            fallback_resolvers: Vec::new(),
         }
     }
 
@@ -34,14 +43,33 @@ impl Namespace {
     pub fn get_instance(&mut self, name: &str) -> Option<&mut Instance> {
         self.names.get_mut(name)
     }
+
+    /// Register `resolver` to be consulted when resolving an import whose
+    /// module name has no instance registered in this `Namespace`. If an
+    /// instance is registered under that name, its lookup result is
+    /// returned as is and fallbacks are not tried, even when it lacks the
+    /// field. Fallbacks are tried in the order they were pushed.
+    pub fn push_fallback(&mut self, resolver: Box<dyn Resolver>) {
+        self.fallback_resolvers.push(resolver);
+    }
+
+    /// Builder-style version of `push_fallback`.
+    pub fn with_fallback(mut self, resolver: Box<dyn Resolver>) -> Self {
+        self.push_fallback(resolver);
+        self
+    }
 }
 
 impl Resolver for Namespace {
     fn resolve(&mut self, name: &str, field: &str) -> Option<Export> {
         if let Some(instance) = self.names.get_mut(name) {
-            instance.lookup(field)
-        } else {
-            None
+            return instance.lookup(field);
+        }
+        for resolver in &mut self.fallback_resolvers {
+            if let Some(export) = resolver.resolve(name, field) {
+                return Some(export);
+            }
         }
+        None
     }
 }